@@ -0,0 +1,300 @@
+//! Parsing and evaluation of SPDX license expressions.
+//!
+//! `cargo-license` reports the `license` field verbatim from each crate's
+//! manifest, which is a full SPDX expression (`MIT OR Apache-2.0`,
+//! `(MIT OR Apache-2.0) AND Unicode-DFS-2016`, `Apache-2.0 WITH
+//! LLVM-exception`, ...), not just a single identifier. This module parses
+//! that expression into a tree and drives the download logic from it: every
+//! leaf under an `AND` must be fetched, while only one alternative under an
+//! `OR` needs to succeed.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use reqwest::blocking::Client;
+use spdx::expression::{ExprNode, Operator};
+use spdx::{Expression, LicenseItem};
+
+use crate::manifest::Resolved;
+use crate::notice::fetch_notice;
+use crate::verify;
+use crate::{crate_file_stem, get_license, Elem, RawFilesURL};
+
+pub const DEFAULT: [&str; 2] = ["LICENSE", "LICENCE"];
+pub const APACHE: [&str; 6] = [
+    "LICENSE-APACHE",
+    "LICENSE-Apache",
+    "License-Apache-2.0",
+    "LICENCE-APACHE",
+    "LICENCE-Apache",
+    "Licence-Apache-2.0",
+];
+pub const BSD2: [&str; 2] = ["LICENSE-BSD", "LICENCE-BSD"];
+pub const BSD3: [&str; 2] = ["LICENSE-BSD", "LICENCE-BSD"];
+pub const BSL: [&str; 4] = [
+    "LICENSE-BOOST",
+    "LICENSE-BST",
+    "LICENCE-BOOST",
+    "LICENCE-BST",
+];
+pub const CC0: [&str; 2] = ["LICENSE", "LICENCE"];
+pub const ISC: [&str; 2] = ["LICENSE-ISC", "LICENCE-ISC"];
+pub const MIT: [&str; 2] = ["LICENSE-MIT", "LICENCE-MIT"];
+pub const MPL_2: [&str; 2] = ["LICENSE", "LICENCE"];
+pub const ZERO_BSD: [&str; 2] = ["LICENSE-0BSD", "LICENCE-0BSD"];
+pub const ZLIB: [&str; 2] = ["LICENSE-ZLIB", "LICENCE-ZLIB"];
+
+/// Candidate filenames for a leaf of the expression tree: the license body
+/// itself, plus an optional `WITH <exception>` file.
+struct LeafFiles {
+    id: String,
+    license: &'static [&'static str],
+    exception: Option<String>,
+}
+
+/// Extracts the plain string identifier from one license term of a parsed
+/// expression: the SPDX short id, or, for a `LicenseRef-*` term the SPDX list
+/// doesn't know about, the part after the `LicenseRef-` prefix (any
+/// `DocumentRef-*` part is dropped).
+pub(crate) fn leaf_id(license: &LicenseItem) -> String {
+    match license {
+        LicenseItem::Spdx { id, .. } => id.name.to_string(),
+        LicenseItem::Other { lic_ref, .. } => lic_ref.clone(),
+    }
+}
+
+/// Looks up the candidate filenames for a (now parsed and validated) SPDX
+/// license identifier.
+///
+/// Unknown-but-valid ids (the SPDX list grows over time) fall back to the
+/// generic `LICENSE`/`LICENCE` names instead of being reported as
+/// "Not implemented": most repositories keep the license body there
+/// regardless of which id it corresponds to.
+fn candidates_for(id: &str) -> &'static [&'static str] {
+    match id {
+        "Apache-2.0" => &APACHE,
+        "MIT" => &MIT,
+        "BSD-3-Clause" => &BSD3,
+        "BSD-2-Clause" => &BSD2,
+        "0BSD" => &ZERO_BSD,
+        "CC0-1.0" => &CC0,
+        "MPL-2.0" => &MPL_2,
+        "BSD" => &BSD3,
+        "ISC" => &ISC,
+        "BSL-1.0" => &BSL,
+        "Zlib" => &ZLIB,
+        "Unlicense" => &[],
+        _ => &DEFAULT,
+    }
+}
+
+enum Node {
+    Leaf(LeafFiles),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+}
+
+/// Builds an evaluation tree from the postfix (reverse Polish) token stream
+/// that the `spdx` crate exposes for a parsed expression.
+fn build_tree(expression: &Expression) -> Node {
+    let mut stack: Vec<Node> = Vec::new();
+    for node in expression.iter() {
+        match node {
+            ExprNode::Req(req_node) => {
+                let id = leaf_id(&req_node.req.license);
+                let exception = req_node
+                    .req
+                    .exception
+                    .map(|exception| exception.name.to_string());
+                stack.push(Node::Leaf(LeafFiles {
+                    license: candidates_for(&id),
+                    id,
+                    exception,
+                }));
+            }
+            ExprNode::Op(op) => {
+                // Postfix: the two most recently pushed nodes are the operands.
+                let rhs = stack.pop().expect("malformed SPDX expression");
+                let lhs = stack.pop().expect("malformed SPDX expression");
+                stack.push(match op {
+                    Operator::And => Node::And(Box::new(lhs), Box::new(rhs)),
+                    Operator::Or => Node::Or(Box::new(lhs), Box::new(rhs)),
+                });
+            }
+        }
+    }
+    stack.pop().expect("empty SPDX expression")
+}
+
+/// Writes the embedded canonical SPDX text for `id` as a fallback when the
+/// upstream repo had no `LICENSE-*` file for it, so the output directory is
+/// always complete. Returns the path written to, if a canonical text was
+/// available for `id`.
+fn write_canonical_fallback(
+    elem: &Elem,
+    id: &str,
+    output_dir: &Path,
+    log: &mut Vec<String>,
+) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    match verify::canonical_text(id) {
+        Some(text) => {
+            fs::create_dir_all(output_dir)?;
+            let local_path =
+                output_dir.join(format!("{}-LICENSE-{}.spdx", crate_file_stem(elem), id));
+            fs::write(&local_path, text)?;
+            log.push(format!(
+                "    - {} (not found upstream; synthesized from the canonical SPDX {} text)",
+                local_path.to_string_lossy(),
+                id
+            ));
+            Ok(Some(local_path))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Walks the evaluation tree, fetching license files as required by its
+/// `AND`/`OR` structure, and reports whether the node was satisfied overall.
+fn fetch_tree(
+    client: &Client,
+    node: &Node,
+    elem: &Elem,
+    base_url: &RawFilesURL,
+    output_dir: &Path,
+    log: &mut Vec<String>,
+    resolved: &mut Vec<Resolved>,
+) -> Result<bool, Box<dyn Error>> {
+    match node {
+        Node::Leaf(leaf) => {
+            if leaf.license.is_empty() {
+                // e.g. Unlicense: no upstream filename to look for, but we
+                // still have a canonical text to synthesize and record.
+                if let Some(path) = write_canonical_fallback(elem, &leaf.id, output_dir, log)? {
+                    resolved.push(Resolved {
+                        id: leaf.id.clone(),
+                        path,
+                    });
+                }
+                return Ok(true);
+            }
+            let mut ok = get_license(
+                client,
+                elem,
+                base_url,
+                Some(&leaf.id),
+                leaf.license,
+                output_dir,
+                log,
+            )?;
+            let mut text_path =
+                output_dir.join(format!("{}-{}", crate_file_stem(elem), leaf.license[0]));
+            if !ok {
+                if let Some(path) = write_canonical_fallback(elem, &leaf.id, output_dir, log)? {
+                    ok = true;
+                    text_path = path;
+                }
+            }
+            if ok {
+                resolved.push(Resolved {
+                    id: leaf.id.clone(),
+                    path: text_path,
+                });
+            }
+            if let Some(exception) = &leaf.exception {
+                let exception_names: Vec<&str> = vec![exception.as_str()];
+                // No canonical text is embedded for exceptions, so any
+                // non-empty response is accepted as before.
+                ok &= get_license(
+                    client,
+                    elem,
+                    base_url,
+                    None,
+                    &exception_names,
+                    output_dir,
+                    log,
+                )?;
+            }
+            if leaf.id == "Apache-2.0" {
+                fetch_notice(client, elem, base_url, output_dir, log)?;
+            }
+            Ok(ok)
+        }
+        Node::And(lhs, rhs) => {
+            // Both sides are legally required, so both are attempted
+            // regardless of whether the other one succeeded.
+            let lhs_ok = fetch_tree(client, lhs, elem, base_url, output_dir, log, resolved)?;
+            let rhs_ok = fetch_tree(client, rhs, elem, base_url, output_dir, log, resolved)?;
+            Ok(lhs_ok && rhs_ok)
+        }
+        Node::Or(lhs, rhs) => {
+            if fetch_tree(client, lhs, elem, base_url, output_dir, log, resolved)? {
+                Ok(true)
+            } else {
+                fetch_tree(client, rhs, elem, base_url, output_dir, log, resolved)
+            }
+        }
+    }
+}
+
+/// Parses `license` as an SPDX expression and fetches every file required to
+/// satisfy it, returning whether the expression was fully satisfied.
+/// Every license id successfully resolved (downloaded or synthesized) is
+/// appended to `resolved`, for the THIRD-PARTY-LICENSES manifest.
+pub fn fetch_license_expression(
+    client: &Client,
+    license: &str,
+    elem: &Elem,
+    base_url: &RawFilesURL,
+    output_dir: &Path,
+    log: &mut Vec<String>,
+    resolved: &mut Vec<Resolved>,
+) -> Result<bool, Box<dyn Error>> {
+    match Expression::parse(license) {
+        Ok(expression) => {
+            let tree = build_tree(&expression);
+            fetch_tree(client, &tree, elem, base_url, output_dir, log, resolved)
+        }
+        Err(e) => {
+            log.push(format!(
+                "Could not parse SPDX expression \"{}\" for crate {}: {}",
+                license, elem.name, e
+            ));
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elem(name: &str) -> Elem {
+        serde_json::from_str(&format!(r#"{{"name": "{}"}}"#, name)).unwrap()
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("get-license-helper-test-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn write_canonical_fallback_writes_the_embedded_text_for_a_known_id() {
+        let dir = scratch_dir("known");
+        let mut log = Vec::new();
+        let path = write_canonical_fallback(&elem("example"), "MIT", &dir, &mut log)
+            .unwrap()
+            .unwrap();
+        assert!(path.is_file());
+        assert!(!log.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_canonical_fallback_is_none_for_an_unknown_id() {
+        let dir = scratch_dir("unknown");
+        let mut log = Vec::new();
+        let result = write_canonical_fallback(&elem("example"), "Some-Unknown-License", &dir, &mut log).unwrap();
+        assert!(result.is_none());
+        assert!(log.is_empty());
+    }
+}
@@ -1,5 +1,14 @@
+mod license_expr;
+mod manifest;
+mod notice;
+mod policy;
+mod reuse;
+mod verify;
+
 use structopt::StructOpt;
 
+use rayon::prelude::*;
+use reqwest::blocking::Client;
 use serde::Deserialize;
 
 use std::error::Error;
@@ -7,30 +16,7 @@ use std::fs::{self, read_to_string};
 use std::io::Read;
 use std::path::{Path, PathBuf};
 
-// Add licence everywhere.
-const DEFAULT: [&str; 2] = ["LICENSE", "LICENCE"];
-const APACHE: [&str; 6] = [
-    "LICENSE-APACHE",
-    "LICENSE-Apache",
-    "License-Apache-2.0",
-    "LICENCE-APACHE",
-    "LICENCE-Apache",
-    "Licence-Apache-2.0",
-];
-const BSD2: [&str; 2] = ["LICENSE-BSD", "LICENCE-BSD"];
-const BSD3: [&str; 2] = ["LICENSE-BSD", "LICENCE-BSD"];
-const BSL: [&str; 4] = [
-    "LICENSE-BOOST",
-    "LICENSE-BST",
-    "LICENCE-BOOST",
-    "LICENCE-BST",
-];
-const CC0: [&str; 2] = ["LICENSE", "LICENCE"];
-const ISC: [&str; 2] = ["LICENSE-ISC", "LICENCE-ISC"];
-const MIT: [&str; 2] = ["LICENSE-MIT", "LICENCE-MIT"];
-const MPL_2: [&str; 2] = ["LICENSE", "LICENCE"];
-const ZERO_BSD: [&str; 2] = ["LICENSE-0BSD", "LICENCE-0BSD"];
-const ZLIB: [&str; 2] = ["LICENSE-ZLIB", "LICENCE-ZLIB"];
+use license_expr::fetch_license_expression;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -44,42 +30,77 @@ struct Args {
     /// Directory storing the licenses
     #[structopt(short = "l", parse(from_os_str), default_value = "library_licenses")]
     license_dir: PathBuf,
+    /// Number of crates to process concurrently
+    #[structopt(short = "j", long = "jobs", default_value = "4")]
+    jobs: usize,
+    /// Path to a license allow/deny policy file; when given, the process
+    /// exits with a non-zero status if any crate's license isn't allowed
+    #[structopt(long = "policy", parse(from_os_str))]
+    policy: Option<PathBuf>,
+    /// Format of the consolidated THIRD-PARTY-LICENSES manifest
+    #[structopt(long = "manifest-format", default_value = "markdown")]
+    manifest_format: manifest::ManifestFormat,
 }
 
 #[derive(Deserialize)]
-struct Elem {
-    name: String,
+pub(crate) struct Elem {
+    pub(crate) name: String,
     version: Option<String>,
     //authors: Option<String>,
-    repository: Option<String>,
+    pub(crate) repository: Option<String>,
     license: Option<String>,
     license_file: Option<String>,
     //description: Option<String>
 }
 
-fn get_license(
+/// Disambiguates per-crate output filenames by version, so that dependency
+/// graphs pulling in multiple versions of the same crate (common with
+/// `syn`, `itertools`, etc.) don't collide on a single `<name>-*` path —
+/// which, now that crates are processed concurrently, would otherwise be a
+/// genuine write-write race rather than just a cosmetic overwrite.
+pub(crate) fn crate_file_stem(elem: &Elem) -> String {
+    match &elem.version {
+        Some(version) => format!("{}-{}", elem.name, version),
+        None => elem.name.clone(),
+    }
+}
+
+/// Downloads one of `license`'s candidate filenames into `output_dir`.
+///
+/// When `license_id` names a license we have canonical SPDX text for, a
+/// candidate is only accepted once its content scores above
+/// [`verify::SIMILARITY_THRESHOLD`] against that canonical text; otherwise
+/// (unknown id, or none given) any non-empty response is accepted as before.
+pub(crate) fn get_license(
+    client: &Client,
     elem: &Elem,
     base_url: &RawFilesURL,
+    license_id: Option<&str>,
     license: &[&str],
     output_dir: &Path,
-) -> Result<(), Box<dyn Error>> {
+    log: &mut Vec<String>,
+) -> Result<bool, Box<dyn Error>> {
     assert!(!license.is_empty());
     fs::create_dir_all(output_dir)?;
-    let local_path = output_dir.join(format!("{}-{}", elem.name, license[0]));
+    let local_path = output_dir.join(format!("{}-{}", crate_file_stem(elem), license[0]));
     // Check if the license file has already been downloaded
     let mut success = local_path.is_file() && fs::metadata(&local_path)?.len() > 0;
+    // Tracks whether some candidate was actually found and read, but
+    // rejected by the similarity check, so the final log message can tell
+    // "nothing existed" apart from "something existed but looked wrong".
+    let mut rejected_candidate = false;
     if !success {
         // Try first with the provided license name (e.g. LICENSE-MIT(.txt|.md)),
         // then with the generic "LICENSE(.txt|.md)"
         let mut license_names = license.to_vec();
         // Add default names if not already the default
-        if license != DEFAULT {
-            license_names.extend_from_slice(&DEFAULT);
+        if license != license_expr::DEFAULT {
+            license_names.extend_from_slice(&license_expr::DEFAULT);
         }
         // Add extensions '.txt' and '.md'
         let license_names: Vec<String> = license_names
             .iter()
-            .map(|l| {
+            .flat_map(|l| {
                 vec![
                     l.to_string(),
                     format!("{}.txt", l),
@@ -89,7 +110,6 @@ fn get_license(
                     format!("{}.md", l.to_lowercase()),
                 ]
             })
-            .flatten()
             .collect();
         // Try first with the version as a tag, else look at the master and main branches.
         let versions = elem
@@ -100,36 +120,62 @@ fn get_license(
         'outer: for license_name in license_names {
             for version in versions.iter() {
                 let url = base_url.format(version, &license_name);
-                let mut resp = reqwest::blocking::get(&url)?;
-                if resp.status().is_success() {
-                    let mut file = std::fs::File::create(&local_path)?;
-                    resp.copy_to(&mut file)?;
-                    success = true;
-                    break 'outer;
+                let resp = client.get(&url).send()?;
+                if !resp.status().is_success() {
+                    continue;
+                }
+                let body = resp.text()?;
+                if body.is_empty() {
+                    continue;
                 }
+                if let Some(id) = license_id {
+                    match verify::similarity_to_canonical(&body, id) {
+                        Some(score) if score < verify::SIMILARITY_THRESHOLD => {
+                            rejected_candidate = true;
+                            log.push(format!(
+                                "    - {} at {} looked unrelated to {} (score {:.2}), skipping",
+                                license_name, url, id, score
+                            ));
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
+                fs::write(&local_path, body)?;
+                success = true;
+                break 'outer;
             }
         }
     }
-    if !success {
-        println!(
+    if !success && rejected_candidate {
+        log.push(format!(
+            "{} for crate {} needs manual review: candidate(s) were found but didn't look like {} \
+             (see repo: {})",
+            license[0],
+            elem.name,
+            license_id.unwrap_or(license[0]),
+            elem.repository.as_ref().unwrap()
+        ));
+    } else if !success {
+        log.push(format!(
             "{} not found for crate {}. See repo: {}",
             license[0],
             elem.name,
             elem.repository.as_ref().unwrap()
-        );
+        ));
     } else {
-        println!("    - {}", local_path.to_string_lossy());
+        log.push(format!("    - {}", local_path.to_string_lossy()));
     }
-    Ok(())
+    Ok(success)
 }
 
-struct RawFilesURL {
+pub(crate) struct RawFilesURL {
     base: String,
     subdirectory: Option<String>,
 }
 
 impl RawFilesURL {
-    fn from_repo_url(repo_url: &str) -> Option<Self> {
+    pub(crate) fn from_repo_url(repo_url: &str) -> Option<Self> {
         let repo_url = repo_url.trim_end_matches(".git");
         if repo_url.starts_with("https://gitlab.") {
             Some(Self {
@@ -163,7 +209,7 @@ impl RawFilesURL {
         }
     }
 
-    fn format(&self, version: &str, filename: &str) -> String {
+    pub(crate) fn format(&self, version: &str, filename: &str) -> String {
         if let Some(subdirectory) = &self.subdirectory {
             format!("{}/{}/{}/{}", self.base, version, subdirectory, filename)
         } else {
@@ -184,63 +230,159 @@ fn get_input_data_as_string(args: &Args) -> std::io::Result<String> {
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::from_args();
-    // Load the full JSON at once
-    let data = get_input_data_as_string(&args)?;
-    // Deserialize to obtain a vector of objects (one per crate)
-    let dependencies: Vec<Elem> = serde_json::from_str(&data)?;
-    // TODO: create a pool of async workers to process n repo at "the same" time
-    for e in dependencies {
-        let repo_url = e
-            .repository
-            .as_ref()
-            .cloned()
-            .unwrap_or(format!("No repo for crate {}!", e.name));
+/// Fetches everything for a single crate, returning its output lines rather
+/// than printing them directly so that results stay in input order even
+/// though crates are processed concurrently.
+fn process_elem(
+    client: &Client,
+    e: &Elem,
+    license_dir: &Path,
+) -> (Vec<String>, Vec<manifest::Resolved>) {
+    let mut log = Vec::new();
+    let mut resolved = Vec::new();
+    let repo_url = e
+        .repository
+        .as_ref()
+        .cloned()
+        .unwrap_or(format!("No repo for crate {}!", e.name));
+    let result: Result<(), Box<dyn Error>> = (|| {
         match RawFilesURL::from_repo_url(&repo_url) {
             Some(url_raw) => {
+                // REUSE metadata, when present, is authoritative for which
+                // per-file licenses apply; only fall back to filename
+                // guessing when the repo doesn't declare any.
+                if reuse::fetch_reuse_licenses(
+                    client,
+                    e,
+                    &url_raw,
+                    license_dir,
+                    &mut log,
+                    &mut resolved,
+                )? {
+                    return Ok(());
+                }
                 match e.license.as_ref() {
                     Some(license) => {
-                        for l in license.split(" OR ") {
-                            // TODO: list to be completed!
-                            match l {
-                                "Apache-2.0" | "Apache-2.0 WITH LLVM-exception" => {
-                                    get_license(&e, &url_raw, &APACHE, &args.license_dir)?
-                                }
-                                "MIT" => get_license(&e, &url_raw, &MIT, &args.license_dir)?,
-                                "BSD-3-Clause" => {
-                                    get_license(&e, &url_raw, &BSD3, &args.license_dir)?
-                                }
-                                "BSD-2-Clause" => {
-                                    get_license(&e, &url_raw, &BSD2, &args.license_dir)?
-                                }
-                                "0BSD" => get_license(&e, &url_raw, &ZERO_BSD, &args.license_dir)?,
-                                "CC0-1.0" => get_license(&e, &url_raw, &CC0, &args.license_dir)?,
-                                "MPL-2.0" => get_license(&e, &url_raw, &MPL_2, &args.license_dir)?,
-                                "BSD" => get_license(&e, &url_raw, &BSD3, &args.license_dir)?,
-                                "ISC" => get_license(&e, &url_raw, &ISC, &args.license_dir)?,
-                                "BSL-1.0" => get_license(&e, &url_raw, &BSL, &args.license_dir)?,
-                                "Zlib" => get_license(&e, &url_raw, &ZLIB, &args.license_dir)?,
-                                "Unlicense" => { /* No license, do nothing. */ }
-                                _ if l.starts_with("Apache-2.0") => {
-                                    get_license(&e, &url_raw, &APACHE, &args.license_dir)?
-                                }
-                                _ => println!(
-                                    "Not implemented: license: {}, see repo: {}",
-                                    l, repo_url
-                                ),
-                            }
+                        if !fetch_license_expression(
+                            client,
+                            license,
+                            e,
+                            &url_raw,
+                            license_dir,
+                            &mut log,
+                            &mut resolved,
+                        )? {
+                            log.push(format!(
+                                "Could not satisfy license expression \"{}\" for crate {}, see repo: {}",
+                                license, e.name, repo_url
+                            ));
                         }
                     }
                     None => match &e.license_file {
                         Some(license) => {
-                            get_license(&e, &url_raw, &[license.as_str()], &args.license_dir)?
+                            get_license(
+                                client,
+                                e,
+                                &url_raw,
+                                None,
+                                &[license.as_str()],
+                                license_dir,
+                                &mut log,
+                            )?;
+                        }
+                        None => {
+                            get_license(
+                                client,
+                                e,
+                                &url_raw,
+                                None,
+                                &license_expr::DEFAULT,
+                                license_dir,
+                                &mut log,
+                            )?;
                         }
-                        None => get_license(&e, &url_raw, &DEFAULT, &args.license_dir)?,
                     },
                 }
             }
-            None => println!("Unfamiliar repository URL: {}", repo_url),
+            None => log.push(format!("Unfamiliar repository URL: {}", repo_url)),
+        }
+        Ok(())
+    })();
+    if let Err(err) = result {
+        log.push(format!("Error processing crate {}: {}", e.name, err));
+    }
+    (log, resolved)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::from_args();
+    // Load the full JSON at once
+    let data = get_input_data_as_string(&args)?;
+    // Deserialize to obtain a vector of objects (one per crate)
+    let dependencies: Vec<Elem> = serde_json::from_str(&data)?;
+    // Created up front so `manifest::write` always has somewhere to write,
+    // even on runs where no crate reaches a download attempt (e.g. an empty
+    // input, or every repository URL going unrecognized).
+    fs::create_dir_all(&args.license_dir)?;
+    let client = Client::new();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()?;
+    // Run N downloads in parallel, but print each crate's output only once
+    // every crate has been processed, in the original dependency order.
+    let results: Vec<(Vec<String>, Vec<manifest::Resolved>)> = pool.install(|| {
+        dependencies
+            .par_iter()
+            .map(|e| process_elem(&client, e, &args.license_dir))
+            .collect()
+    });
+
+    let mut manifest_entries = Vec::with_capacity(results.len());
+    for (e, (log, resolved)) in dependencies.iter().zip(results) {
+        for line in log {
+            println!("{}", line);
+        }
+        manifest_entries.push((
+            manifest::CrateRef {
+                name: e.name.clone(),
+                version: e.version.clone(),
+            },
+            resolved,
+        ));
+    }
+
+    let licenses = manifest::build(&manifest_entries);
+    let manifest_path = manifest::write(&licenses, args.manifest_format, &args.license_dir)?;
+    println!(
+        "\nWrote consolidated license manifest to {}",
+        manifest_path.to_string_lossy()
+    );
+
+    if let Some(policy_path) = &args.policy {
+        let policy = policy::Policy::load(policy_path)?;
+        let violations: Vec<policy::Violation> = dependencies
+            .iter()
+            .filter_map(|e| match e.license.as_ref() {
+                Some(license) => policy.check(e, license),
+                // No `license` field means there's nothing to check against
+                // the allow/exceptions lists, not that the crate is fine.
+                None => Some(policy::Policy::missing_license(e)),
+            })
+            .collect();
+        if !violations.is_empty() {
+            println!("\nLicense policy violations:");
+            for violation in &violations {
+                println!(
+                    "  - {}: \"{}\" (repo: {})",
+                    violation.crate_name,
+                    violation.license_expression,
+                    violation
+                        .repository
+                        .as_deref()
+                        .unwrap_or("<unknown repository>")
+                );
+            }
+            std::process::exit(1);
         }
     }
     Ok(())
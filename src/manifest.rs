@@ -0,0 +1,168 @@
+//! Generating a consolidated `THIRD-PARTY-LICENSES` manifest, grouping the
+//! processed dependencies by their resolved SPDX license rather than
+//! leaving a scratch directory of `<crate>-<file>` files and stdout lines
+//! as the only output.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+/// A crate and the version of it that was processed.
+#[derive(Serialize, Clone)]
+pub struct CrateRef {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+/// One SPDX id resolved for a crate, and the file its text was stored in.
+pub struct Resolved {
+    pub id: String,
+    pub path: PathBuf,
+}
+
+/// A distinct SPDX license together with every crate that uses it.
+#[derive(Serialize)]
+pub struct License {
+    pub id: String,
+    pub text_path: PathBuf,
+    pub used_by: Vec<CrateRef>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Markdown,
+    Json,
+}
+
+impl FromStr for ManifestFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "json" => Ok(Self::Json),
+            other => Err(format!(
+                "unknown manifest format \"{}\" (expected \"markdown\" or \"json\")",
+                other
+            )),
+        }
+    }
+}
+
+/// Groups every `(crate, resolved licenses)` pair by SPDX id.
+pub fn build(entries: &[(CrateRef, Vec<Resolved>)]) -> Vec<License> {
+    let mut by_id: BTreeMap<String, License> = BTreeMap::new();
+    for (crate_ref, resolved) in entries {
+        for r in resolved {
+            let license = by_id.entry(r.id.clone()).or_insert_with(|| License {
+                id: r.id.clone(),
+                text_path: r.path.clone(),
+                used_by: Vec::new(),
+            });
+            license.used_by.push(crate_ref.clone());
+        }
+    }
+    by_id.into_values().collect()
+}
+
+/// Writes `licenses` to `output_dir` in the requested format, returning the
+/// path written to.
+pub fn write(
+    licenses: &[License],
+    format: ManifestFormat,
+    output_dir: &Path,
+) -> Result<PathBuf, Box<dyn Error>> {
+    match format {
+        ManifestFormat::Json => {
+            let path = output_dir.join("THIRD-PARTY-LICENSES.json");
+            fs::write(&path, serde_json::to_string_pretty(licenses)?)?;
+            Ok(path)
+        }
+        ManifestFormat::Markdown => {
+            let path = output_dir.join("THIRD-PARTY-LICENSES.md");
+            let mut out = String::from("# Third-party licenses\n\n");
+            for license in licenses {
+                out.push_str(&format!("## {}\n\n", license.id));
+                out.push_str(&format!(
+                    "License text: `{}`\n\n",
+                    license.text_path.display()
+                ));
+                out.push_str("Used by:\n\n");
+                for crate_ref in &license.used_by {
+                    match &crate_ref.version {
+                        Some(version) => out.push_str(&format!("- {} {}\n", crate_ref.name, version)),
+                        None => out.push_str(&format!("- {}\n", crate_ref.name)),
+                    }
+                }
+                out.push('\n');
+            }
+            fs::write(&path, out)?;
+            Ok(path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_groups_crates_by_license_id() {
+        let entries = vec![
+            (
+                CrateRef {
+                    name: "serde".to_string(),
+                    version: Some("1.0.0".to_string()),
+                },
+                vec![Resolved {
+                    id: "MIT".to_string(),
+                    path: PathBuf::from("serde-1.0.0-LICENSE-MIT"),
+                }],
+            ),
+            (
+                CrateRef {
+                    name: "rand".to_string(),
+                    version: Some("0.8.0".to_string()),
+                },
+                vec![Resolved {
+                    id: "MIT".to_string(),
+                    path: PathBuf::from("rand-0.8.0-LICENSE-MIT"),
+                }],
+            ),
+            (
+                CrateRef {
+                    name: "ring".to_string(),
+                    version: None,
+                },
+                vec![Resolved {
+                    id: "ISC".to_string(),
+                    path: PathBuf::from("ring-LICENSE-ISC"),
+                }],
+            ),
+        ];
+
+        let licenses = build(&entries);
+        assert_eq!(licenses.len(), 2);
+
+        let mit = licenses.iter().find(|l| l.id == "MIT").unwrap();
+        assert_eq!(mit.used_by.len(), 2);
+        assert!(mit.used_by.iter().any(|c| c.name == "serde"));
+        assert!(mit.used_by.iter().any(|c| c.name == "rand"));
+
+        let isc = licenses.iter().find(|l| l.id == "ISC").unwrap();
+        assert_eq!(isc.used_by.len(), 1);
+        assert_eq!(isc.used_by[0].name, "ring");
+    }
+
+    #[test]
+    fn manifest_format_parses_its_cli_aliases() {
+        assert_eq!("markdown".parse::<ManifestFormat>().unwrap(), ManifestFormat::Markdown);
+        assert_eq!("md".parse::<ManifestFormat>().unwrap(), ManifestFormat::Markdown);
+        assert_eq!("json".parse::<ManifestFormat>().unwrap(), ManifestFormat::Json);
+        assert!("xml".parse::<ManifestFormat>().is_err());
+    }
+}
@@ -0,0 +1,57 @@
+//! Fetching Apache NOTICE files alongside the license body.
+//!
+//! Apache License 2.0 §4(d) requires redistributing any `NOTICE` file the
+//! upstream project ships, which plain `LICENSE-APACHE` fetching never
+//! looks for. This module probes for one and saves it as
+//! `<crate>-<version>-NOTICE` (`<crate>-NOTICE` when the version is unknown)
+//! when present; unlike the license body, a missing NOTICE file is not an
+//! error, since most Apache-2.0 projects don't ship one.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use reqwest::blocking::Client;
+
+use crate::{crate_file_stem, Elem, RawFilesURL};
+
+const NOTICE_NAMES: [&str; 3] = ["NOTICE", "NOTICE.txt", "NOTICE.md"];
+
+/// Probes `base_url` for a NOTICE file at the crate's version (falling back
+/// to `master`/`main`) and saves it as `<crate>-<version>-NOTICE` (or
+/// `<crate>-NOTICE` without a version) if found.
+pub fn fetch_notice(
+    client: &Client,
+    elem: &Elem,
+    base_url: &RawFilesURL,
+    output_dir: &Path,
+    log: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(output_dir)?;
+    let local_path = output_dir.join(format!("{}-NOTICE", crate_file_stem(elem)));
+    if local_path.is_file() && fs::metadata(&local_path)?.len() > 0 {
+        return Ok(());
+    }
+    let versions = elem
+        .version
+        .as_ref()
+        .map(|version| vec![version.as_str(), "master", "main"])
+        .unwrap_or_else(|| vec!["master", "main"]);
+    for notice_name in NOTICE_NAMES {
+        for version in versions.iter() {
+            let url = base_url.format(version, notice_name);
+            let resp = client.get(&url).send()?;
+            if !resp.status().is_success() {
+                continue;
+            }
+            let body = resp.text()?;
+            if body.is_empty() {
+                continue;
+            }
+            fs::write(&local_path, body)?;
+            log.push(format!("    - {}", local_path.to_string_lossy()));
+            return Ok(());
+        }
+    }
+    Ok(())
+}
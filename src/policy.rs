@@ -0,0 +1,172 @@
+//! Enforcing an allow/deny license policy, so the tool can gate CI rather
+//! than just gather files.
+//!
+//! The policy is a TOML file with a global `allow` list of acceptable SPDX
+//! identifiers and a per-crate `exceptions` table for the handful of crates
+//! that need special dispensation beyond the allowlist:
+//!
+//! ```toml
+//! allow = ["MIT", "Apache-2.0", "BSD-3-Clause"]
+//!
+//! [exceptions]
+//! some-gpl-crate = ["GPL-3.0-only"]
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use serde::Deserialize;
+use spdx::{Expression, LicenseItem};
+
+use crate::Elem;
+
+#[derive(Deserialize)]
+struct PolicyFile {
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    exceptions: HashMap<String, Vec<String>>,
+}
+
+pub struct Policy {
+    allow: HashSet<String>,
+    exceptions: HashMap<String, HashSet<String>>,
+}
+
+/// A crate whose license expression isn't satisfied by the policy.
+pub struct Violation {
+    pub crate_name: String,
+    pub license_expression: String,
+    pub repository: Option<String>,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = read_to_string(path)?;
+        let parsed: PolicyFile = toml::from_str(&contents)?;
+        Ok(Self {
+            allow: parsed.allow.into_iter().collect(),
+            exceptions: parsed
+                .exceptions
+                .into_iter()
+                .map(|(crate_name, ids)| (crate_name, ids.into_iter().collect()))
+                .collect(),
+        })
+    }
+
+    /// Checks `elem`'s SPDX `license_expression` against the policy,
+    /// returning a [`Violation`] if it isn't satisfied.
+    ///
+    /// An expression is satisfied when every leaf required by its `AND`/`OR`
+    /// structure resolves to an id that is either globally allowed or
+    /// granted as an exception for this specific crate.
+    pub fn check(&self, elem: &Elem, license_expression: &str) -> Option<Violation> {
+        let allowed = |id: &str| -> bool {
+            self.allow.contains(id)
+                || self
+                    .exceptions
+                    .get(&elem.name)
+                    .is_some_and(|ids| ids.contains(id))
+        };
+        let satisfied = match Expression::parse(license_expression) {
+            Ok(expression) => expression.evaluate(|req| match &req.license {
+                LicenseItem::Spdx { id, .. } => allowed(id.name),
+                LicenseItem::Other { lic_ref, .. } => allowed(lic_ref),
+            }),
+            // An expression we can't even parse can't be said to satisfy
+            // the policy.
+            Err(_) => false,
+        };
+        if satisfied {
+            None
+        } else {
+            Some(Violation {
+                crate_name: elem.name.clone(),
+                license_expression: license_expression.to_string(),
+                repository: elem.repository.clone(),
+            })
+        }
+    }
+
+    /// Builds the violation reported for a crate with no `license` field at
+    /// all (e.g. one that only sets `license_file`): unknown licensing can't
+    /// be checked against the allow/exceptions lists, so it's reported
+    /// rather than silently passing the gate.
+    pub fn missing_license(elem: &Elem) -> Violation {
+        Violation {
+            crate_name: elem.name.clone(),
+            license_expression: "<no license field>".to_string(),
+            repository: elem.repository.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elem(name: &str) -> Elem {
+        serde_json::from_str(&format!(r#"{{"name": "{}"}}"#, name)).unwrap()
+    }
+
+    fn policy() -> Policy {
+        let parsed: PolicyFile = toml::from_str(
+            r#"
+            allow = ["MIT", "Apache-2.0"]
+
+            [exceptions]
+            some-copyleft-crate = ["ISC"]
+            "#,
+        )
+        .unwrap();
+        Policy {
+            allow: parsed.allow.into_iter().collect(),
+            exceptions: parsed
+                .exceptions
+                .into_iter()
+                .map(|(crate_name, ids)| (crate_name, ids.into_iter().collect()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn allowed_expression_is_not_a_violation() {
+        assert!(policy().check(&elem("serde"), "MIT OR Apache-2.0").is_none());
+    }
+
+    #[test]
+    fn disallowed_expression_is_a_violation() {
+        let violation = policy().check(&elem("weird-crate"), "ISC").unwrap();
+        assert_eq!(violation.crate_name, "weird-crate");
+    }
+
+    #[test]
+    fn per_crate_exception_allows_an_otherwise_disallowed_id() {
+        assert!(policy()
+            .check(&elem("some-copyleft-crate"), "ISC")
+            .is_none());
+    }
+
+    #[test]
+    fn exception_does_not_apply_to_other_crates() {
+        assert!(policy().check(&elem("other-crate"), "ISC").is_some());
+    }
+
+    #[test]
+    fn an_and_expression_needs_every_leaf_allowed() {
+        assert!(policy().check(&elem("serde"), "MIT AND ISC").is_some());
+    }
+
+    #[test]
+    fn an_unparseable_expression_is_a_violation() {
+        assert!(policy().check(&elem("serde"), "not a real expression").is_some());
+    }
+
+    #[test]
+    fn missing_license_is_reported_as_a_violation() {
+        let violation = Policy::missing_license(&elem("no-license-field-crate"));
+        assert_eq!(violation.crate_name, "no-license-field-crate");
+    }
+}
@@ -0,0 +1,221 @@
+//! Honoring the REUSE specification (<https://reuse.software>) for per-file
+//! license data.
+//!
+//! Many repositories now declare licensing authoritatively via a top-level
+//! `REUSE.toml` (or the older `.reuse/dep5`), rather than via a single
+//! top-level `LICENSE-*` file. When either is present we trust it over our
+//! filename-guessing heuristic: it tells us exactly which SPDX ids are in
+//! play and, per the REUSE convention, their texts live under
+//! `LICENSES/<id>.txt`.
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use spdx::Expression;
+
+use crate::license_expr::leaf_id;
+use crate::manifest::Resolved;
+use crate::verify;
+use crate::{crate_file_stem, Elem, RawFilesURL};
+
+#[derive(Deserialize)]
+struct ReuseToml {
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+}
+
+#[derive(Deserialize)]
+struct Annotation {
+    #[serde(rename = "SPDX-License-Identifier")]
+    spdx_license_identifier: String,
+    // `SPDX-FileCopyrightText` is part of the spec but isn't needed to
+    // locate the license texts, so it's not modeled here.
+}
+
+/// Unique leaf ids referenced by an `SPDX-License-Identifier`-style
+/// expression, via the same expression parser request #1 uses for the
+/// `license` manifest field — so parens and `WITH` exceptions are handled
+/// correctly instead of split on naively.
+fn ids_from_expression(expression: &str) -> BTreeSet<String> {
+    match Expression::parse(expression) {
+        Ok(expression) => expression
+            .requirements()
+            .map(|req| leaf_id(&req.req.license))
+            .collect(),
+        Err(_) => BTreeSet::new(),
+    }
+}
+
+/// Unique SPDX ids declared via `[[annotations]]` in `REUSE.toml`.
+fn ids_from_reuse_toml(contents: &str) -> BTreeSet<String> {
+    let parsed: ReuseToml = match toml::from_str(contents) {
+        Ok(parsed) => parsed,
+        Err(_) => return BTreeSet::new(),
+    };
+    parsed
+        .annotations
+        .into_iter()
+        .flat_map(|annotation| ids_from_expression(&annotation.spdx_license_identifier))
+        .collect()
+}
+
+/// Unique SPDX ids declared via `License:` fields of a `.reuse/dep5`
+/// (Debian machine-readable copyright format) file.
+fn ids_from_dep5(contents: &str) -> BTreeSet<String> {
+    contents
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("License:"))
+        .flat_map(ids_from_expression)
+        .collect()
+}
+
+/// Fetches `REUSE.toml` or `.reuse/dep5` for `elem`'s repository and, if
+/// either is present, downloads `LICENSES/<id>.txt` for each SPDX id they
+/// declare. Returns `true` if REUSE metadata was found (regardless of how
+/// many license texts were actually fetched), so the caller knows whether
+/// to fall back to filename guessing.
+pub fn fetch_reuse_licenses(
+    client: &Client,
+    elem: &Elem,
+    base_url: &RawFilesURL,
+    output_dir: &Path,
+    log: &mut Vec<String>,
+    resolved: &mut Vec<Resolved>,
+) -> Result<bool, Box<dyn Error>> {
+    let versions = elem
+        .version
+        .as_ref()
+        .map(|version| vec![version.as_str(), "master", "main"])
+        .unwrap_or_else(|| vec!["master", "main"]);
+
+    let mut ids = BTreeSet::new();
+    let mut found_metadata = false;
+    'versions: for version in &versions {
+        for (path, parse) in [
+            ("REUSE.toml", ids_from_reuse_toml as fn(&str) -> BTreeSet<String>),
+            (".reuse/dep5", ids_from_dep5),
+        ] {
+            let url = base_url.format(version, path);
+            let resp = client.get(&url).send()?;
+            if !resp.status().is_success() {
+                continue;
+            }
+            let body = resp.text()?;
+            if body.is_empty() {
+                continue;
+            }
+            found_metadata = true;
+            ids.extend(parse(&body));
+            break 'versions;
+        }
+    }
+    if !found_metadata {
+        return Ok(false);
+    }
+
+    fs::create_dir_all(output_dir)?;
+    for id in ids {
+        let license_path = format!("LICENSES/{}.txt", id);
+        let local_path =
+            output_dir.join(format!("{}-LICENSES-{}.txt", crate_file_stem(elem), id));
+        if local_path.is_file() && fs::metadata(&local_path)?.len() > 0 {
+            log.push(format!("    - {}", local_path.to_string_lossy()));
+            resolved.push(Resolved {
+                id: id.clone(),
+                path: local_path,
+            });
+            continue;
+        }
+        let mut downloaded = false;
+        for version in &versions {
+            let url = base_url.format(version, &license_path);
+            let resp = client.get(&url).send()?;
+            if !resp.status().is_success() {
+                continue;
+            }
+            let body = resp.text()?;
+            if body.is_empty() {
+                continue;
+            }
+            if let Some(score) = verify::similarity_to_canonical(&body, &id) {
+                if score < verify::SIMILARITY_THRESHOLD {
+                    log.push(format!(
+                        "    - {} at {} looked unrelated to {} (score {:.2}), skipping",
+                        license_path, url, id, score
+                    ));
+                    continue;
+                }
+            }
+            fs::write(&local_path, body)?;
+            log.push(format!("    - {}", local_path.to_string_lossy()));
+            resolved.push(Resolved {
+                id: id.clone(),
+                path: local_path.clone(),
+            });
+            downloaded = true;
+            break;
+        }
+        if !downloaded {
+            log.push(format!(
+                "{} not found for crate {} despite REUSE metadata declaring it",
+                license_path, elem.name
+            ));
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_from_expression_handles_parens_and_with() {
+        let ids = ids_from_expression("MIT AND (Apache-2.0 OR BSD-2-Clause)");
+        assert_eq!(
+            ids,
+            BTreeSet::from(["MIT".to_string(), "Apache-2.0".to_string(), "BSD-2-Clause".to_string()])
+        );
+
+        let ids = ids_from_expression("Apache-2.0 WITH LLVM-exception");
+        assert_eq!(ids, BTreeSet::from(["Apache-2.0".to_string()]));
+    }
+
+    #[test]
+    fn ids_from_dep5_reads_license_fields() {
+        let contents = "\
+Format: https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/
+Upstream-Name: example
+
+Files: *
+Copyright: 2024 Example Authors
+License: MIT AND (Apache-2.0 OR BSD-2-Clause)
+";
+        let ids = ids_from_dep5(contents);
+        assert_eq!(
+            ids,
+            BTreeSet::from(["MIT".to_string(), "Apache-2.0".to_string(), "BSD-2-Clause".to_string()])
+        );
+    }
+
+    #[test]
+    fn ids_from_reuse_toml_reads_annotations() {
+        let contents = r#"
+[[annotations]]
+path = "src/lib.rs"
+SPDX-FileCopyrightText = "2024 Example Authors"
+SPDX-License-Identifier = "MIT"
+
+[[annotations]]
+path = "vendor/**"
+SPDX-FileCopyrightText = "2024 Example Authors"
+SPDX-License-Identifier = "Apache-2.0 WITH LLVM-exception"
+"#;
+        let ids = ids_from_reuse_toml(contents);
+        assert_eq!(ids, BTreeSet::from(["MIT".to_string(), "Apache-2.0".to_string()]));
+    }
+}
@@ -0,0 +1,129 @@
+//! Verifying that a downloaded file actually contains the license text it
+//! claims to, instead of trusting any HTTP 200 with a non-zero body (which
+//! also matches an HTML 404 page or an unrelated file).
+//!
+//! The downloaded text and the canonical SPDX license text are both
+//! normalized (lowercased, punctuation stripped, whitespace collapsed,
+//! copyright/author header lines dropped) and compared with a
+//! Sørensen–Dice coefficient over their token trigrams, the same style of
+//! fuzzy match cargo-about uses for its license detection.
+
+use std::collections::HashSet;
+
+/// Minimum similarity score, out of 1.0, for a downloaded file to be
+/// accepted as a match for the expected license.
+pub const SIMILARITY_THRESHOLD: f64 = 0.9;
+
+/// Returns the canonical SPDX license text for `id`, if one is embedded.
+pub fn canonical_text(id: &str) -> Option<&'static str> {
+    match id {
+        "MIT" => Some(include_str!("licenses/MIT.txt")),
+        "Apache-2.0" => Some(include_str!("licenses/Apache-2.0.txt")),
+        "BSD-2-Clause" => Some(include_str!("licenses/BSD-2-Clause.txt")),
+        "BSD-3-Clause" | "BSD" => Some(include_str!("licenses/BSD-3-Clause.txt")),
+        "0BSD" => Some(include_str!("licenses/0BSD.txt")),
+        "CC0-1.0" => Some(include_str!("licenses/CC0-1.0.txt")),
+        "MPL-2.0" => Some(include_str!("licenses/MPL-2.0.txt")),
+        "ISC" => Some(include_str!("licenses/ISC.txt")),
+        "BSL-1.0" => Some(include_str!("licenses/BSL-1.0.txt")),
+        "Zlib" => Some(include_str!("licenses/Zlib.txt")),
+        "Unlicense" => Some(include_str!("licenses/Unlicense.txt")),
+        _ => None,
+    }
+}
+
+/// Lowercases, strips punctuation, collapses whitespace, and drops
+/// copyright/author header lines (which legitimately differ between a
+/// project's copy and the canonical text).
+fn normalize(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for line in text.lines() {
+        let lower = line.trim().to_lowercase();
+        if lower.starts_with("copyright") || lower.starts_with("(c)") || lower.starts_with("©") {
+            continue;
+        }
+        // A line break counts as whitespace between the last word of the
+        // previous line and the first word of this one.
+        if !out.is_empty() && !out.ends_with(' ') {
+            out.push(' ');
+        }
+        let mut prev_was_space = true;
+        for ch in lower.chars() {
+            if ch.is_alphanumeric() {
+                out.push(ch);
+                prev_was_space = false;
+            } else if !prev_was_space {
+                out.push(' ');
+                prev_was_space = true;
+            }
+        }
+    }
+    out.trim().to_string()
+}
+
+/// The set of token (word) trigrams of a normalized text.
+fn token_trigrams(normalized: &str) -> HashSet<(&str, &str, &str)> {
+    let words: Vec<&str> = normalized.split_whitespace().collect();
+    if words.len() < 3 {
+        return HashSet::new();
+    }
+    words.windows(3).map(|w| (w[0], w[1], w[2])).collect()
+}
+
+/// Sørensen–Dice coefficient between the token trigrams of two normalized
+/// texts: `2 * |A ∩ B| / (|A| + |B|)`.
+fn dice_coefficient(a: &str, b: &str) -> f64 {
+    let a_grams = token_trigrams(a);
+    let b_grams = token_trigrams(b);
+    if a_grams.is_empty() || b_grams.is_empty() {
+        return 0.0;
+    }
+    let intersection = a_grams.intersection(&b_grams).count();
+    2.0 * intersection as f64 / (a_grams.len() + b_grams.len()) as f64
+}
+
+/// Scores `downloaded` against the canonical text for `id`, if we have one
+/// embedded. Returns `None` when there is nothing to compare against, in
+/// which case the caller should fall back to accepting any non-empty file.
+pub fn similarity_to_canonical(downloaded: &str, id: &str) -> Option<f64> {
+    canonical_text(id).map(|reference| dice_coefficient(&normalize(downloaded), &normalize(reference)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_drops_copyright_lines_and_punctuation() {
+        let text = "Copyright (c) 2024 Jane Doe\nPermission is hereby granted, free of charge!";
+        assert_eq!(
+            normalize(text),
+            "permission is hereby granted free of charge"
+        );
+    }
+
+    #[test]
+    fn dice_coefficient_is_one_for_identical_text() {
+        let text = "the quick brown fox jumps over the lazy dog";
+        assert_eq!(dice_coefficient(text, text), 1.0);
+    }
+
+    #[test]
+    fn dice_coefficient_is_zero_for_unrelated_text() {
+        let a = "the quick brown fox jumps over the lazy dog";
+        let b = "all rights reserved under the applicable jurisdiction";
+        assert_eq!(dice_coefficient(a, b), 0.0);
+    }
+
+    #[test]
+    fn similarity_to_canonical_accepts_a_real_mit_text_with_a_different_copyright_line() {
+        let downloaded = format!("Copyright (c) 2024 Someone Else\n\n{}", canonical_text("MIT").unwrap());
+        let score = similarity_to_canonical(&downloaded, "MIT").unwrap();
+        assert!(score > SIMILARITY_THRESHOLD, "score was {}", score);
+    }
+
+    #[test]
+    fn similarity_to_canonical_is_none_for_an_unknown_id() {
+        assert_eq!(similarity_to_canonical("whatever", "Some-Unknown-License"), None);
+    }
+}